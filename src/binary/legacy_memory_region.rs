@@ -1,23 +1,79 @@
-use crate::memory_map::MemoryRegion;
+use crate::memory_map::{MemoryRegion, MemoryRegionKind};
+use arrayvec::ArrayVec;
 use core::mem::MaybeUninit;
 use x86_64::{
-    structures::paging::{FrameAllocator, PhysFrame, Size4KiB},
-    PhysAddr,
+    structures::paging::{
+        FrameAllocator, FrameDeallocator, PageSize, PhysFrame, PhysFrameRange, Size2MiB, Size4KiB,
+    },
+    PhysAddr, VirtAddr,
 };
 
 pub trait LegacyMemoryRegion: Copy + core::fmt::Debug {
     fn start(&self) -> PhysAddr;
     fn len(&self) -> u64;
     fn usable(&self) -> bool;
+    /// The firmware-reported kind of this region, for descriptors where
+    /// `usable()` is `false`. Lets callers distinguish e.g. reclaimable ACPI
+    /// memory from memory that is permanently off-limits.
+    fn kind(&self) -> FirmwareMemoryKind;
 
     fn set_start(&mut self, new_start: PhysAddr);
 }
 
+/// A firmware-independent classification of a memory map descriptor, as
+/// reported by [`LegacyMemoryRegion::kind`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FirmwareMemoryKind {
+    /// Free, usable memory.
+    Usable,
+    /// Reserved by the firmware, with no more specific classification.
+    Reserved,
+    /// Memory holding ACPI tables that can be reclaimed once parsed.
+    AcpiReclaimable,
+    /// Memory used by ACPI for non-volatile sleep state data.
+    AcpiNvs,
+    /// Memory reported as defective.
+    Bad,
+    /// A firmware-specific type code that doesn't map to any of the other
+    /// variants.
+    Unknown(u32),
+}
+
+/// Maximum number of frames we expect to be on the free list at the point
+/// `construct_memory_map` is called. The free list is only ever populated by
+/// the bootloader freeing its own scratch frames, so this is generous.
+const MAX_FREED_FRAMES: usize = 64;
+
+/// Maximum number of multi-frame gaps recorded by
+/// [`record_alignment_skip`](LegacyFrameAllocator::record_alignment_skip)
+/// across the lifetime of an allocator. Each huge-page allocation records
+/// one gap per reserved range it has to skip past, plus one gap in front of
+/// the frame it returns unless `next_frame` is already aligned (it is not,
+/// e.g., after a 4 KiB allocation). Recording more gaps than this panics.
+const MAX_ALIGNMENT_SKIPS: usize = 16;
+
+/// Maximum number of free extents (individually free-listed frames, plus
+/// alignment-skip ranges) `construct_memory_map` has to thread through at
+/// once.
+const MAX_FREE_RANGES: usize = MAX_FREED_FRAMES + MAX_ALIGNMENT_SKIPS;
+
+/// Maximum number of caller-reserved physical ranges (kernel image, initrd,
+/// firmware structures, ...) tracked by a single allocator.
+pub(crate) const MAX_RESERVED_RANGES: usize = 8;
+
 pub struct LegacyFrameAllocator<I, D> {
     original: I,
     memory_map: I,
     current_descriptor: Option<D>,
     next_frame: PhysFrame,
+    physical_memory_offset: VirtAddr,
+    free_list_head: Option<PhysFrame>,
+    reserved: ArrayVec<PhysFrameRange, MAX_RESERVED_RANGES>,
+    /// Multi-frame gaps skipped by `allocate_frame_aligned` to satisfy
+    /// alignment, tracked separately from the free list so a single huge
+    /// allocation can't blow `MAX_FREED_FRAMES`.
+    alignment_skips: ArrayVec<PhysFrameRange, MAX_ALIGNMENT_SKIPS>,
 }
 
 impl<I, D> LegacyFrameAllocator<I, D>
@@ -25,21 +81,76 @@ where
     I: ExactSizeIterator<Item = D> + Clone,
     I::Item: LegacyMemoryRegion,
 {
-    pub fn new(memory_map: I) -> Self {
+    /// Marks the frame's contents as "no next frame" in the free list encoding.
+    const FREE_LIST_END: u64 = u64::MAX;
+
+    pub fn new(memory_map: I, physical_memory_offset: VirtAddr) -> Self {
         // skip frame 0 because the rust core library does not see 0 as a valid address
         let start_frame = PhysFrame::containing_address(PhysAddr::new(0x1000));
-        Self::new_starting_at(start_frame, memory_map)
+        Self::new_starting_at(start_frame, memory_map, physical_memory_offset)
     }
 
-    pub fn new_starting_at(frame: PhysFrame, memory_map: I) -> Self {
+    pub fn new_starting_at(
+        frame: PhysFrame,
+        memory_map: I,
+        physical_memory_offset: VirtAddr,
+    ) -> Self {
         Self {
             original: memory_map.clone(),
             memory_map,
             current_descriptor: None,
             next_frame: frame,
+            physical_memory_offset,
+            free_list_head: None,
+            reserved: ArrayVec::new(),
+            alignment_skips: ArrayVec::new(),
         }
     }
 
+    /// Like [`new`](Self::new), but additionally marks `reserved` as
+    /// physical ranges that must never be handed out, e.g. because they
+    /// overlap the loaded kernel image, the initrd, or firmware structures
+    /// the bootloader still needs.
+    pub fn new_with_reserved(
+        memory_map: I,
+        physical_memory_offset: VirtAddr,
+        reserved: impl IntoIterator<Item = PhysFrameRange>,
+    ) -> Self {
+        let mut allocator = Self::new(memory_map, physical_memory_offset);
+        for range in reserved {
+            allocator.reserve(range);
+        }
+        allocator
+    }
+
+    /// Marks `range` as reserved, so that future calls to `allocate_frame`
+    /// will never hand out a frame inside it.
+    pub fn reserve(&mut self, range: PhysFrameRange) {
+        self.reserved
+            .try_push(range)
+            .expect("too many reserved physical memory ranges");
+    }
+
+    /// Returns the reserved range that contains `frame`, if any.
+    fn reserved_range_containing(&self, frame: PhysFrame) -> Option<PhysFrameRange> {
+        self.reserved
+            .iter()
+            .copied()
+            .find(|range| range.start <= frame && frame < range.end)
+    }
+
+    /// Returns the first reserved range overlapping `[start, end)`, if any.
+    fn reserved_range_overlapping_frames(
+        &self,
+        start: PhysFrame,
+        end: PhysFrame,
+    ) -> Option<PhysFrameRange> {
+        self.reserved
+            .iter()
+            .copied()
+            .find(|range| range.start < end && range.end > start)
+    }
+
     fn allocate_frame_from_descriptor(&mut self, descriptor: D) -> Option<PhysFrame> {
         let start_addr = descriptor.start();
         let start_frame = PhysFrame::containing_address(start_addr);
@@ -51,15 +162,319 @@ where
             self.next_frame = start_frame;
         }
 
-        if self.next_frame < end_frame {
-            let ret = self.next_frame;
+        loop {
+            if self.next_frame >= end_frame {
+                return None;
+            }
+
+            let candidate = self.next_frame;
+            if let Some(reserved) = self.reserved_range_containing(candidate) {
+                // skip past the reserved range and retry within this descriptor
+                self.next_frame = reserved.end;
+                continue;
+            }
+
             self.next_frame += 1;
-            Some(ret)
-        } else {
-            None
+            return Some(candidate);
         }
     }
 
+    /// Allocates `count` physically contiguous 4 KiB frames, bypassing the
+    /// free list: frames recycled through `deallocate_frame` are not
+    /// guaranteed to be adjacent to one another, so callers needing a
+    /// contiguous backing buffer (e.g.
+    /// [`BitmapFrameAllocator`](super::bitmap_frame_allocator::BitmapFrameAllocator)'s
+    /// bitmap) must go through here instead of repeated single-frame
+    /// `allocate_frame` calls.
+    pub(crate) fn allocate_contiguous_frames(&mut self, count: u64) -> Option<PhysFrame> {
+        if let Some(current_descriptor) = self.current_descriptor {
+            match self.allocate_contiguous_from_descriptor(current_descriptor, count) {
+                Some(frame) => return Some(frame),
+                None => {
+                    self.current_descriptor = None;
+                }
+            }
+        }
+
+        while let Some(descriptor) = self.memory_map.next() {
+            if !descriptor.usable() {
+                continue;
+            }
+            if let Some(frame) = self.allocate_contiguous_from_descriptor(descriptor, count) {
+                self.current_descriptor = Some(descriptor);
+                return Some(frame);
+            }
+        }
+
+        None
+    }
+
+    fn allocate_contiguous_from_descriptor(
+        &mut self,
+        descriptor: D,
+        count: u64,
+    ) -> Option<PhysFrame> {
+        let start_addr = descriptor.start();
+        let start_frame = PhysFrame::containing_address(start_addr);
+        let end_addr = start_addr + descriptor.len();
+        let end_frame = PhysFrame::containing_address(end_addr - 1u64);
+
+        if self.next_frame < start_frame {
+            self.next_frame = start_frame;
+        }
+
+        loop {
+            let candidate = self.next_frame;
+            let last_needed_frame = candidate + (count - 1);
+
+            if last_needed_frame >= end_frame {
+                return None;
+            }
+
+            if let Some(reserved) =
+                self.reserved_range_overlapping_frames(candidate, last_needed_frame + 1)
+            {
+                // skip past the reserved range and retry within this descriptor
+                self.next_frame = reserved.end;
+                continue;
+            }
+
+            self.next_frame = candidate + count;
+            return Some(candidate);
+        }
+    }
+
+    /// Allocates a frame whose start address is aligned to `align` (which
+    /// must be a power of two multiple of the 4 KiB frame size, e.g.
+    /// `Size2MiB::SIZE`), entirely within `descriptor` and outside of any
+    /// caller-reserved range.
+    ///
+    /// Returns `None` if the aligned frame plus the frames it spans don't
+    /// fit before the end of `descriptor`; the caller should then retry with
+    /// the next descriptor. The 4 KiB frames skipped over to satisfy
+    /// alignment are not lost: they are recorded by
+    /// [`record_alignment_skip`](Self::record_alignment_skip) so
+    /// `construct_memory_map` can still report them as `Usable`, without
+    /// individually free-listing all of them (a single 2 MiB alignment step
+    /// can skip up to 511 frames, far more than `MAX_FREED_FRAMES`).
+    fn allocate_frame_aligned(&mut self, descriptor: D, align: u64) -> Option<PhysFrame> {
+        let start_addr = descriptor.start();
+        let start_frame = PhysFrame::containing_address(start_addr);
+        let end_addr = start_addr + descriptor.len();
+        let end_frame = PhysFrame::containing_address(end_addr - 1u64);
+
+        if self.next_frame < start_frame {
+            self.next_frame = start_frame;
+        }
+
+        let frame_count = align / Size4KiB::SIZE;
+
+        loop {
+            let skipped_start = self.next_frame;
+            let aligned_frame =
+                PhysFrame::containing_address(self.next_frame.start_address().align_up(align));
+            let last_needed_frame = aligned_frame + (frame_count - 1);
+
+            if last_needed_frame >= end_frame {
+                return None;
+            }
+
+            if let Some(reserved) =
+                self.reserved_range_overlapping_frames(aligned_frame, last_needed_frame + 1)
+            {
+                // the aligned frame would overlap a caller-reserved range;
+                // the gap leading up to it is still free, record it, then
+                // skip past the reserved range and retry alignment further
+                // into the descriptor
+                self.record_alignment_skip(skipped_start, aligned_frame);
+                self.next_frame = reserved.end;
+                continue;
+            }
+
+            self.record_alignment_skip(skipped_start, aligned_frame);
+            self.next_frame = aligned_frame + frame_count;
+            return Some(aligned_frame);
+        }
+    }
+
+    /// Records `[start, end)` as 4 KiB frames that were skipped over to
+    /// satisfy an alignment requirement, excluding any caller-reserved
+    /// sub-range the same way `construct_memory_map` carves reserved ranges
+    /// out of usable descriptors, so reserved memory is never reported back
+    /// as free.
+    fn record_alignment_skip(&mut self, start: PhysFrame, end: PhysFrame) {
+        if start >= end {
+            return;
+        }
+
+        let mut cursor = start;
+        for reserved in self.reserved_ranges_overlapping(start.start_address(), end.start_address())
+        {
+            let reserved_start = reserved.start.max(cursor);
+            let reserved_end = reserved.end.min(end);
+            if reserved_start >= reserved_end {
+                continue;
+            }
+            if cursor < reserved_start {
+                self.push_alignment_skip(cursor, reserved_start);
+            }
+            cursor = reserved_end;
+        }
+        if cursor < end {
+            self.push_alignment_skip(cursor, end);
+        }
+    }
+
+    fn push_alignment_skip(&mut self, start: PhysFrame, end: PhysFrame) {
+        self.alignment_skips
+            .try_push(PhysFrameRange { start, end })
+            .expect("too many huge-page alignment gaps to track");
+    }
+
+    /// Returns a pointer to the given frame's contents, through the
+    /// bootloader's physical memory mapping. Used to store the intrusive
+    /// free-list link in a freed frame's first 8 bytes.
+    fn free_list_node_ptr(&self, frame: PhysFrame) -> *mut u64 {
+        let virt = self.physical_memory_offset + frame.start_address().as_u64();
+        virt.as_mut_ptr()
+    }
+
+    /// Pops the head of the free list, if any, following the link stored in
+    /// its first 8 bytes to find the new head.
+    fn pop_free_frame(&mut self) -> Option<PhysFrame> {
+        let frame = self.free_list_head?;
+        let next = unsafe { self.free_list_node_ptr(frame).read() };
+        self.free_list_head = (next != Self::FREE_LIST_END)
+            .then(|| PhysFrame::containing_address(PhysAddr::new(next)));
+        Some(frame)
+    }
+
+    /// Walks the free list and combines it with the ranges recorded by
+    /// [`record_alignment_skip`](Self::record_alignment_skip), returning all
+    /// currently-reclaimable extents in ascending order.
+    fn sorted_free_ranges(&self) -> ArrayVec<PhysFrameRange, MAX_FREE_RANGES> {
+        let mut free_ranges: ArrayVec<PhysFrameRange, MAX_FREE_RANGES> = ArrayVec::new();
+
+        let mut next = self.free_list_head;
+        while let Some(frame) = next {
+            free_ranges
+                .try_push(PhysFrameRange {
+                    start: frame,
+                    end: frame + 1,
+                })
+                .expect("too many freed frames to report in the memory map");
+            let raw_next = unsafe { self.free_list_node_ptr(frame).read() };
+            next = (raw_next != Self::FREE_LIST_END)
+                .then(|| PhysFrame::containing_address(PhysAddr::new(raw_next)));
+        }
+
+        for &range in self.alignment_skips.iter() {
+            free_ranges
+                .try_push(range)
+                .expect("too many freed frames to report in the memory map");
+        }
+
+        free_ranges.sort_unstable_by_key(|range| range.start);
+        free_ranges
+    }
+
+    /// Total bytes currently reclaimable: frames on the intrusive free list,
+    /// plus frames skipped to satisfy huge-page alignment.
+    fn freed_bytes(&self) -> u64 {
+        self.sorted_free_ranges()
+            .iter()
+            .map(|range| range.end.start_address().as_u64() - range.start.start_address().as_u64())
+            .sum()
+    }
+
+    fn total_usable_bytes(&self) -> u64 {
+        self.original
+            .clone()
+            .filter(|d| d.usable())
+            .map(|d| d.len())
+            .sum()
+    }
+
+    /// Returns the total amount of physical memory reported by the
+    /// firmware, usable or not. Caller-reserved ranges are intentionally
+    /// still counted here: they are physical memory that exists, just not
+    /// memory this allocator will hand out; see
+    /// [`usable_memory_bytes`](Self::usable_memory_bytes) for the figure
+    /// that excludes them.
+    pub fn total_memory_bytes(&self) -> u64 {
+        self.original.clone().map(|d| d.len()).sum()
+    }
+
+    /// Returns the number of bytes the bootloader has consumed out of the
+    /// usable memory so far, net of anything it has since freed.
+    pub fn bootloader_used_bytes(&self) -> u64 {
+        let next_free = self.next_frame.start_address();
+        let consumed: u64 = self
+            .original
+            .clone()
+            .filter(|d| d.usable())
+            .map(|d| {
+                let start = d.start();
+                let end = start + d.len();
+                if end <= next_free {
+                    d.len()
+                } else if start >= next_free {
+                    0
+                } else {
+                    (next_free - start)
+                }
+            })
+            .sum();
+
+        consumed.saturating_sub(self.freed_bytes())
+    }
+
+    /// Returns the number of usable bytes locked away by caller-reserved
+    /// ranges that the bump allocator has not yet advanced past.
+    ///
+    /// Only the portion of each reserved range at or after `next_frame` is
+    /// counted: everything before `next_frame` is already folded into
+    /// [`bootloader_used_bytes`](Self::bootloader_used_bytes) (which treats
+    /// all usable bytes behind `next_frame` as consumed, whether reserved or
+    /// actually handed out), so counting it again here would double-subtract
+    /// it from [`usable_memory_bytes`](Self::usable_memory_bytes). Likewise,
+    /// only the overlap with *usable* descriptors is counted, since reserved
+    /// bytes inside a non-usable descriptor were never part of
+    /// [`total_usable_bytes`](Self::total_usable_bytes) in the first place.
+    fn unconsumed_reserved_bytes(&self) -> u64 {
+        let next_free = self.next_frame.start_address();
+        self.original
+            .clone()
+            .filter(|d| d.usable())
+            .map(|d| {
+                let descriptor_start = d.start();
+                let descriptor_end = descriptor_start + d.len();
+                self.reserved
+                    .iter()
+                    .map(|range| {
+                        let overlap_start = range
+                            .start
+                            .start_address()
+                            .max(descriptor_start)
+                            .max(next_free);
+                        let overlap_end = range.end.start_address().min(descriptor_end);
+                        overlap_end.as_u64().saturating_sub(overlap_start.as_u64())
+                    })
+                    .sum::<u64>()
+            })
+            .sum()
+    }
+
+    /// Returns the number of usable bytes still available to be handed out,
+    /// i.e. the usable memory the bootloader hasn't consumed (or has since
+    /// freed back to the free list), excluding memory the caller has
+    /// reserved but the bump allocator hasn't reached yet.
+    pub fn usable_memory_bytes(&self) -> u64 {
+        self.total_usable_bytes()
+            .saturating_sub(self.bootloader_used_bytes())
+            .saturating_sub(self.unconsumed_reserved_bytes())
+    }
+
     pub fn len(&self) -> usize {
         self.original.len()
     }
@@ -72,50 +487,255 @@ where
             .unwrap()
     }
 
+    /// Consumes the allocator, returning the original memory map, the first
+    /// frame it had not yet handed out, the caller-reserved ranges it was
+    /// tracking, and the extents it already knew to be reclaimable (the free
+    /// list plus recorded alignment-skip ranges). Used by
+    /// [`BitmapFrameAllocator`](super::bitmap_frame_allocator::BitmapFrameAllocator)
+    /// to take over from the bump allocator once its backing bitmap has been
+    /// carved out; both the reserved ranges and the free ranges must still
+    /// be accounted for in the bitmap, since neither is reachable from
+    /// `original`/`next_frame` alone, and a frame below `next_frame` is not
+    /// necessarily still in use just because the bump pointer has passed it.
+    pub(crate) fn into_parts(
+        self,
+    ) -> (
+        I,
+        PhysFrame,
+        ArrayVec<PhysFrameRange, MAX_RESERVED_RANGES>,
+        ArrayVec<PhysFrameRange, MAX_FREE_RANGES>,
+    ) {
+        let free_ranges = self.sorted_free_ranges();
+        (self.original, self.next_frame, self.reserved, free_ranges)
+    }
+
     pub fn construct_memory_map(
         self,
         regions: &mut [MaybeUninit<MemoryRegion>],
     ) -> &mut [MemoryRegion] {
-        use crate::memory_map::MemoryRegionKind;
-
         let mut next_index = 0;
+        let free_ranges = self.sorted_free_ranges();
+        let mut free_ranges = free_ranges.as_slice();
 
-        for mut descriptor in self.original {
-            let end = descriptor.start() + descriptor.len();
-            let next_free = self.next_frame.start_address();
-            let kind = if descriptor.usable() {
-                if end <= next_free {
-                    MemoryRegionKind::Bootloader
-                } else if descriptor.start() >= next_free {
-                    MemoryRegionKind::Usable
-                } else {
-                    // part of the region is used -> add is separately
-                    let used_region = MemoryRegion {
-                        start: descriptor.start().as_u64(),
-                        end: next_free.as_u64(),
-                        kind: MemoryRegionKind::Bootloader,
-                    };
-                    Self::add_region(used_region, regions, &mut next_index)
-                        .expect("Failed to add memory region");
+        for descriptor in self.original.clone() {
+            let start = descriptor.start();
+            let end = start + descriptor.len();
+
+            if !descriptor.usable() {
+                let kind = match descriptor.kind() {
+                    FirmwareMemoryKind::AcpiReclaimable => MemoryRegionKind::AcpiReclaimable,
+                    FirmwareMemoryKind::AcpiNvs => MemoryRegionKind::AcpiNvs,
+                    FirmwareMemoryKind::Bad => MemoryRegionKind::BadMemory,
+                    FirmwareMemoryKind::Unknown(raw_type) => {
+                        MemoryRegionKind::UnknownFirmware(raw_type)
+                    }
+                    FirmwareMemoryKind::Usable | FirmwareMemoryKind::Reserved => {
+                        MemoryRegionKind::Reserved
+                    }
+                };
+                let region = MemoryRegion {
+                    start: start.as_u64(),
+                    end: end.as_u64(),
+                    kind,
+                };
+                Self::add_region(region, regions, &mut next_index).unwrap();
+                continue;
+            }
 
-                    // add unused part normally
-                    descriptor.set_start(next_free);
-                    MemoryRegionKind::Usable
+            // split out any caller-reserved ranges overlapping this descriptor
+            // as their own `Reserved` regions, the same way a partially-used
+            // descriptor is split below
+            let mut cursor = start;
+            for reserved in self.reserved_ranges_overlapping(start, end) {
+                let reserved_start = reserved.start.start_address().max(cursor);
+                let reserved_end = reserved.end.start_address().min(end);
+                if reserved_start >= reserved_end {
+                    continue;
                 }
-            } else {
-                MemoryRegionKind::Reserved // FIXME more types
+
+                if cursor < reserved_start {
+                    free_ranges = self.add_usable_range(
+                        cursor,
+                        reserved_start,
+                        free_ranges,
+                        regions,
+                        &mut next_index,
+                    );
+                }
+
+                let region = MemoryRegion {
+                    start: reserved_start.as_u64(),
+                    end: reserved_end.as_u64(),
+                    kind: MemoryRegionKind::Reserved,
+                };
+                Self::add_region(region, regions, &mut next_index).unwrap();
+
+                cursor = reserved_end;
+            }
+
+            if cursor < end {
+                free_ranges =
+                    self.add_usable_range(cursor, end, free_ranges, regions, &mut next_index);
+            }
+        }
+
+        let initialized = &mut regions[..next_index];
+        unsafe { MaybeUninit::slice_assume_init_mut(initialized) }
+    }
+
+    /// Returns the reserved ranges overlapping `[start, end)`, sorted in
+    /// ascending order.
+    fn reserved_ranges_overlapping(
+        &self,
+        start: PhysAddr,
+        end: PhysAddr,
+    ) -> ArrayVec<PhysFrameRange, MAX_RESERVED_RANGES> {
+        let mut overlapping: ArrayVec<PhysFrameRange, MAX_RESERVED_RANGES> = self
+            .reserved
+            .iter()
+            .copied()
+            .filter(|range| range.start.start_address() < end && range.end.start_address() > start)
+            .collect();
+        overlapping.sort_unstable_by_key(|range| range.start);
+        overlapping
+    }
+
+    /// Classifies `[start, end)` — a sub-range of a usable descriptor with
+    /// no caller-reserved memory in it — as `Bootloader` or `Usable` against
+    /// `self.next_frame`, splitting at the boundary if necessary, and adds
+    /// the resulting region(s) to `regions`. Returns the remaining,
+    /// unconsumed tail of `free_ranges`.
+    fn add_usable_range<'f>(
+        &self,
+        start: PhysAddr,
+        end: PhysAddr,
+        free_ranges: &'f [PhysFrameRange],
+        regions: &mut [MaybeUninit<MemoryRegion>],
+        next_index: &mut usize,
+    ) -> &'f [PhysFrameRange] {
+        let next_free = self.next_frame.start_address();
+
+        let (effective_start, kind) = if end <= next_free {
+            (start, MemoryRegionKind::Bootloader)
+        } else if start >= next_free {
+            (start, MemoryRegionKind::Usable)
+        } else {
+            // part of the range is used -> add it separately
+            let used_region = MemoryRegion {
+                start: start.as_u64(),
+                end: next_free.as_u64(),
+                kind: MemoryRegionKind::Bootloader,
             };
+            let free_ranges =
+                Self::add_bootloader_region(used_region, free_ranges, regions, next_index);
 
+            // add unused part normally
             let region = MemoryRegion {
-                start: descriptor.start().as_u64(),
+                start: next_free.as_u64(),
                 end: end.as_u64(),
-                kind,
+                kind: MemoryRegionKind::Usable,
             };
-            Self::add_region(region, regions, &mut next_index).unwrap();
+            Self::add_region(region, regions, next_index).unwrap();
+            return free_ranges;
+        };
+
+        let region = MemoryRegion {
+            start: effective_start.as_u64(),
+            end: end.as_u64(),
+            kind,
+        };
+
+        if kind == MemoryRegionKind::Bootloader {
+            Self::add_bootloader_region(region, free_ranges, regions, next_index)
+        } else {
+            Self::add_region(region, regions, next_index).unwrap();
+            free_ranges
         }
+    }
 
-        let initialized = &mut regions[..next_index];
-        unsafe { MaybeUninit::slice_assume_init_mut(initialized) }
+    /// Adds a `Bootloader`-kind region to `regions`, splitting out any
+    /// currently-reclaimable extents (free-listed frames or recorded
+    /// alignment-skip ranges) overlapping it as their own `Usable` regions,
+    /// so reclaimed memory isn't reported as permanently lost to the
+    /// bootloader.
+    ///
+    /// `free_ranges` must be sorted in ascending order. A range that extends
+    /// beyond `region.end` is left in place (not consumed) so the next call,
+    /// covering the following region, can still see and clip it. Returns the
+    /// remaining, unconsumed tail of `free_ranges`.
+    fn add_bootloader_region<'f>(
+        region: MemoryRegion,
+        free_ranges: &'f [PhysFrameRange],
+        regions: &mut [MaybeUninit<MemoryRegion>],
+        next_index: &mut usize,
+    ) -> &'f [PhysFrameRange] {
+        let mut cursor = region.start;
+        let mut free_ranges = free_ranges;
+
+        while let Some((&range, rest)) = free_ranges.split_first() {
+            let range_start = range.start.start_address().as_u64();
+            let range_end = range.end.start_address().as_u64();
+
+            if range_end <= cursor {
+                // already behind an earlier, lower region
+                free_ranges = rest;
+                continue;
+            }
+            if range_start >= region.end {
+                break;
+            }
+
+            let clipped_start = range_start.max(cursor);
+            let clipped_end = range_end.min(region.end);
+
+            if clipped_start > cursor {
+                Self::add_region(
+                    MemoryRegion {
+                        start: cursor,
+                        end: clipped_start,
+                        kind: MemoryRegionKind::Bootloader,
+                    },
+                    regions,
+                    next_index,
+                )
+                .expect("Failed to add memory region");
+            }
+
+            Self::add_region(
+                MemoryRegion {
+                    start: clipped_start,
+                    end: clipped_end,
+                    kind: MemoryRegionKind::Usable,
+                },
+                regions,
+                next_index,
+            )
+            .expect("Failed to add memory region");
+
+            cursor = clipped_end;
+
+            if range_end > region.end {
+                // this range extends past the current region; leave it in
+                // place for whichever region comes next
+                break;
+            }
+            free_ranges = rest;
+        }
+
+        if cursor < region.end {
+            Self::add_region(
+                MemoryRegion {
+                    start: cursor,
+                    end: region.end,
+                    kind: MemoryRegionKind::Bootloader,
+                },
+                regions,
+                next_index,
+            )
+            .expect("Failed to add memory region");
+        }
+
+        free_ranges
     }
 
     fn add_region(
@@ -141,6 +761,10 @@ where
     I::Item: LegacyMemoryRegion,
 {
     fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+        if let Some(frame) = self.pop_free_frame() {
+            return Some(frame);
+        }
+
         if let Some(current_descriptor) = self.current_descriptor {
             match self.allocate_frame_from_descriptor(current_descriptor) {
                 Some(frame) => return Some(frame),
@@ -164,3 +788,271 @@ where
         None
     }
 }
+
+unsafe impl<I, D> FrameDeallocator<Size4KiB> for LegacyFrameAllocator<I, D>
+where
+    I: ExactSizeIterator<Item = D> + Clone,
+    I::Item: LegacyMemoryRegion,
+{
+    /// Pushes `frame` onto the intrusive free list, writing the current list
+    /// head into the frame's first 8 bytes through the bootloader's physical
+    /// memory mapping.
+    ///
+    /// # Safety
+    ///
+    /// `frame` must not still be in use, and must be reachable through the
+    /// physical memory mapping passed to the allocator's constructor.
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size4KiB>) {
+        let next = self
+            .free_list_head
+            .map(|f| f.start_address().as_u64())
+            .unwrap_or(Self::FREE_LIST_END);
+        unsafe { self.free_list_node_ptr(frame).write(next) };
+        self.free_list_head = Some(frame);
+    }
+}
+
+unsafe impl<I, D> FrameAllocator<Size2MiB> for LegacyFrameAllocator<I, D>
+where
+    I: ExactSizeIterator<Item = D> + Clone,
+    I::Item: LegacyMemoryRegion,
+{
+    /// Allocates a 2 MiB-aligned, 2 MiB-sized frame, so the caller can map
+    /// large contiguous physical regions (e.g. the physical-memory offset
+    /// mapping) with far fewer page-table frames than 4 KiB pages would
+    /// need.
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size2MiB>> {
+        if let Some(current_descriptor) = self.current_descriptor {
+            match self.allocate_frame_aligned(current_descriptor, Size2MiB::SIZE) {
+                Some(frame) => return Some(PhysFrame::containing_address(frame.start_address())),
+                None => {
+                    self.current_descriptor = None;
+                }
+            }
+        }
+
+        while let Some(descriptor) = self.memory_map.next() {
+            if !descriptor.usable() {
+                continue;
+            }
+            if let Some(frame) = self.allocate_frame_aligned(descriptor, Size2MiB::SIZE) {
+                self.current_descriptor = Some(descriptor);
+                return Some(PhysFrame::containing_address(frame.start_address()));
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::vec;
+
+    #[derive(Debug, Copy, Clone)]
+    struct TestRegion {
+        start: PhysAddr,
+        len: u64,
+        usable: bool,
+    }
+
+    impl LegacyMemoryRegion for TestRegion {
+        fn start(&self) -> PhysAddr {
+            self.start
+        }
+
+        fn len(&self) -> u64 {
+            self.len
+        }
+
+        fn usable(&self) -> bool {
+            self.usable
+        }
+
+        fn kind(&self) -> FirmwareMemoryKind {
+            if self.usable {
+                FirmwareMemoryKind::Usable
+            } else {
+                FirmwareMemoryKind::Reserved
+            }
+        }
+
+        fn set_start(&mut self, new_start: PhysAddr) {
+            self.start = new_start;
+        }
+    }
+
+    /// A heap-backed stand-in for the bootloader's physical memory mapping,
+    /// so the intrusive free list (which writes its "next" pointer directly
+    /// through `physical_memory_offset`) has somewhere real to write to.
+    struct FakePhysicalMemory {
+        buffer: std::vec::Vec<u8>,
+        base: PhysAddr,
+    }
+
+    impl FakePhysicalMemory {
+        fn new(base: PhysAddr, len: u64) -> Self {
+            Self {
+                buffer: vec![0u8; len as usize],
+                base,
+            }
+        }
+
+        fn physical_memory_offset(&self) -> VirtAddr {
+            VirtAddr::new(self.buffer.as_ptr() as u64 - self.base.as_u64())
+        }
+    }
+
+    type TestAllocator = LegacyFrameAllocator<std::vec::IntoIter<TestRegion>, TestRegion>;
+
+    fn allocator(memory: &FakePhysicalMemory, regions: std::vec::Vec<TestRegion>) -> TestAllocator {
+        let start_frame = PhysFrame::containing_address(regions[0].start());
+        LegacyFrameAllocator::new_starting_at(
+            start_frame,
+            regions.into_iter(),
+            memory.physical_memory_offset(),
+        )
+    }
+
+    #[test]
+    fn construct_memory_map_splits_out_reserved_ranges() {
+        let memory = FakePhysicalMemory::new(PhysAddr::new(0x1000), 0x5000);
+        let region = TestRegion {
+            start: PhysAddr::new(0x1000),
+            len: 0x5000,
+            usable: true,
+        };
+        let mut alloc = allocator(&memory, vec![region]);
+        alloc.reserve(PhysFrameRange {
+            start: PhysFrame::containing_address(PhysAddr::new(0x3000)),
+            end: PhysFrame::containing_address(PhysAddr::new(0x4000)),
+        });
+
+        let mut storage = [MaybeUninit::uninit(); 8];
+        let map = alloc.construct_memory_map(&mut storage).to_vec();
+
+        assert_eq!(
+            map,
+            std::vec![
+                MemoryRegion {
+                    start: 0x1000,
+                    end: 0x3000,
+                    kind: MemoryRegionKind::Usable,
+                },
+                MemoryRegion {
+                    start: 0x3000,
+                    end: 0x4000,
+                    kind: MemoryRegionKind::Reserved,
+                },
+                MemoryRegion {
+                    start: 0x4000,
+                    end: 0x6000,
+                    kind: MemoryRegionKind::Usable,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn construct_memory_map_reports_deallocated_frames_as_usable() {
+        let memory = FakePhysicalMemory::new(PhysAddr::new(0x1000), 0x5000);
+        let region = TestRegion {
+            start: PhysAddr::new(0x1000),
+            len: 0x5000,
+            usable: true,
+        };
+        let mut alloc = allocator(&memory, vec![region]);
+
+        let first = alloc.allocate_frame().unwrap();
+        let second = alloc.allocate_frame().unwrap();
+        let _third = alloc.allocate_frame().unwrap();
+        assert_eq!(second.start_address(), PhysAddr::new(0x2000));
+        unsafe { alloc.deallocate_frame(second) };
+
+        let mut storage = [MaybeUninit::uninit(); 8];
+        let map = alloc.construct_memory_map(&mut storage).to_vec();
+
+        assert_eq!(
+            map,
+            std::vec![
+                MemoryRegion {
+                    start: 0x1000,
+                    end: 0x2000,
+                    kind: MemoryRegionKind::Bootloader,
+                },
+                MemoryRegion {
+                    start: 0x2000,
+                    end: 0x3000,
+                    kind: MemoryRegionKind::Usable,
+                },
+                MemoryRegion {
+                    start: 0x3000,
+                    end: 0x4000,
+                    kind: MemoryRegionKind::Bootloader,
+                },
+                MemoryRegion {
+                    start: 0x4000,
+                    end: 0x6000,
+                    kind: MemoryRegionKind::Usable,
+                },
+            ]
+        );
+        assert_eq!(first.start_address(), PhysAddr::new(0x1000));
+    }
+
+    #[test]
+    fn allocate_frame_aligned_bounds_huge_skip_instead_of_free_listing_every_frame() {
+        // A 2 MiB alignment step starting one frame in skips 511 4 KiB
+        // frames - far more than MAX_FREED_FRAMES (64). They must be
+        // recorded as a single alignment-skip range, not explode the free
+        // list.
+        let memory = FakePhysicalMemory::new(PhysAddr::new(0x1000), 0x400000);
+        let region = TestRegion {
+            start: PhysAddr::new(0x1000),
+            len: 0x400000,
+            usable: true,
+        };
+        let mut alloc = allocator(&memory, vec![region]);
+
+        let huge_frame: PhysFrame<Size2MiB> = alloc.allocate_frame().unwrap();
+        assert_eq!(huge_frame.start_address(), PhysAddr::new(0x200000));
+
+        let free_ranges = alloc.sorted_free_ranges();
+        assert_eq!(free_ranges.len(), 1);
+        assert_eq!(free_ranges[0].start.start_address(), PhysAddr::new(0x1000));
+        assert_eq!(free_ranges[0].end.start_address(), PhysAddr::new(0x200000));
+    }
+
+    #[test]
+    fn allocate_frame_aligned_skips_past_reserved_range() {
+        let memory = FakePhysicalMemory::new(PhysAddr::new(0x1000), 0x600000);
+        let region = TestRegion {
+            start: PhysAddr::new(0x1000),
+            len: 0x600000,
+            usable: true,
+        };
+        let mut alloc = allocator(&memory, vec![region]);
+        // overlaps the first 2 MiB-aligned candidate at 0x200000
+        alloc.reserve(PhysFrameRange {
+            start: PhysFrame::containing_address(PhysAddr::new(0x200000)),
+            end: PhysFrame::containing_address(PhysAddr::new(0x201000)),
+        });
+
+        let huge_frame: PhysFrame<Size2MiB> = alloc.allocate_frame().unwrap();
+
+        // the allocator must have skipped past the reserved candidate
+        // instead of handing it out
+        assert_eq!(huge_frame.start_address(), PhysAddr::new(0x400000));
+
+        // the reserved frame must not show up as reclaimable either
+        for range in alloc.sorted_free_ranges() {
+            assert!(
+                range.end.start_address() <= PhysAddr::new(0x200000)
+                    || range.start.start_address() >= PhysAddr::new(0x201000)
+            );
+        }
+    }
+}
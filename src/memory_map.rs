@@ -0,0 +1,34 @@
+//! Types describing the physical memory map handed off to the kernel.
+
+/// A region of physical memory, as seen by the bootloader.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(C)]
+pub struct MemoryRegion {
+    pub start: u64,
+    pub end: u64,
+    pub kind: MemoryRegionKind,
+}
+
+/// Represents what a region of physical memory is used for.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum MemoryRegionKind {
+    /// Free, usable memory.
+    Usable,
+    /// Memory used by the bootloader itself (page tables, stack, boot info, ...).
+    Bootloader,
+    /// Memory reported as reserved by the firmware, or explicitly reserved
+    /// by the caller of the frame allocator.
+    Reserved,
+    /// Memory holding ACPI tables that can be reclaimed once they have been
+    /// parsed.
+    AcpiReclaimable,
+    /// Memory used by ACPI for non-volatile sleep state data; must not be
+    /// reclaimed.
+    AcpiNvs,
+    /// Memory reported as defective by the firmware.
+    BadMemory,
+    /// Memory reported with a firmware-specific type code that doesn't map
+    /// to any of the other variants.
+    UnknownFirmware(u32),
+}